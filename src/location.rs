@@ -0,0 +1,564 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AgentId, CommodityName, LocationId, Market, Money, Trade, TradeOffer};
+
+// Flat per-unit-distance rate and a value-based surcharge (insurance against
+// loss in transit), loosely modeled on the city/flight economics of the
+// DrugWars bot.
+const TRANSPORT_BASE_RATE: f64 = 0.1;
+const TRANSPORT_VALUE_SURCHARGE_RATE: f64 = 0.01;
+
+#[derive(Debug)]
+pub enum TransportError {
+    UnknownAgent,
+    UnknownLocation,
+    InsufficientFunds,
+    /// The agent holds none of `commodity_name` to bring along.
+    InsufficientStock,
+}
+
+/// A single location's order book and trade history. Clearing happens
+/// independently per `LocalMarket`, so prices can diverge between locations.
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct LocalMarket {
+    pub coordinates: (f64, f64),
+    pub buy_offers: HashMap<CommodityName, BinaryHeap<TradeOffer>>,
+    pub sell_offers: HashMap<CommodityName, BinaryHeap<Reverse<TradeOffer>>>,
+    pub trades: HashMap<CommodityName, Vec<Trade>>,
+}
+
+impl LocalMarket {
+    // TODO: Memoize
+    pub(crate) fn historic_price(&self, commodity_name: &CommodityName) -> Money {
+        match self.trades.get(commodity_name) {
+            Some(trades) if !trades.is_empty() => Money::from_raw(
+                trades.iter().map(|trade| trade.price.raw()).sum::<i64>() / trades.len() as i64,
+            ),
+            _ => Money::ZERO,
+        }
+    }
+
+    pub(crate) fn historic_prices(&self) -> HashMap<CommodityName, Money> {
+        self.trades
+            .keys()
+            .map(|commodity_name| (commodity_name.clone(), self.historic_price(commodity_name)))
+            .collect()
+    }
+}
+
+impl Market {
+    pub fn add_location(&mut self, coordinates: (f64, f64)) -> LocationId {
+        let location_id = LocationId::new_v4();
+
+        self.locations.insert(
+            location_id,
+            LocalMarket {
+                coordinates,
+                ..LocalMarket::default()
+            },
+        );
+
+        location_id
+    }
+
+    pub(crate) fn get_historic_price(
+        &self,
+        location_id: &LocationId,
+        commodity_name: &CommodityName,
+    ) -> Money {
+        match self.locations.get(location_id) {
+            Some(local_market) => local_market.historic_price(commodity_name),
+            None => Money::ZERO,
+        }
+    }
+
+    /// Relocates `agent_id` to `to`, charging a distance- and bulk-dependent
+    /// fee deducted from its balance. Agents carry a single inventory rather
+    /// than per-location stock, so there's no partial move to speak of:
+    /// "transporting" `commodity_name` brings the agent's *entire* held
+    /// amount of it along, and the fee is scaled by that full amount rather
+    /// than by a caller-supplied quantity that wouldn't actually bound what
+    /// moves. Errors if the agent holds none of `commodity_name` at all.
+    pub fn transport(
+        &mut self,
+        agent_id: AgentId,
+        to: LocationId,
+        commodity_name: &CommodityName,
+    ) -> Result<(), TransportError> {
+        let agent = self.agents.get(&agent_id).ok_or(TransportError::UnknownAgent)?;
+        let from = agent.location_id;
+        let amount = agent.inventory_amount(commodity_name);
+
+        if amount == 0 {
+            return Err(TransportError::InsufficientStock);
+        }
+
+        let (from_coordinates, to_coordinates) = {
+            let from_market = self
+                .locations
+                .get(&from)
+                .ok_or(TransportError::UnknownLocation)?;
+            let to_market = self
+                .locations
+                .get(&to)
+                .ok_or(TransportError::UnknownLocation)?;
+
+            (from_market.coordinates, to_market.coordinates)
+        };
+
+        let distance = ((to_coordinates.0 - from_coordinates.0).powi(2)
+            + (to_coordinates.1 - from_coordinates.1).powi(2))
+        .sqrt();
+
+        let value_surcharge = self.get_historic_price(&from, commodity_name).raw() as f64
+            * TRANSPORT_VALUE_SURCHARGE_RATE;
+        let cost = Money::from_raw(
+            (distance * amount as f64 * (TRANSPORT_BASE_RATE + value_surcharge)).round() as i64,
+        );
+
+        let agent = self.agents.get_mut(&agent_id).unwrap();
+        agent.balance = agent
+            .balance
+            .checked_sub_nonnegative(cost)
+            .map_err(|_| TransportError::InsufficientFunds)?;
+        agent.location_id = to;
+
+        Ok(())
+    }
+
+    /// Collects bid/ask `TradeOffer`s from every agent, grouped by the
+    /// location they belong to, and clears each location's order book
+    /// independently so prices can diverge regionally. Evaluates each
+    /// scripted agent's script just for this call; prefer `run_step` when
+    /// pairing this with `run_production_step`, so a scripted agent's script
+    /// runs once for both instead of once per call.
+    pub fn run_market_step(&mut self) {
+        let scripted_decisions = self.run_scripts();
+        self.run_market_step_with_decisions(scripted_decisions);
+    }
+
+    pub(crate) fn run_market_step_with_decisions(
+        &mut self,
+        mut scripted_decisions: HashMap<AgentId, crate::scripting::ScriptedDecision>,
+    ) {
+        let mut location_ids: Vec<LocationId> = self.locations.keys().cloned().collect();
+        location_ids.sort();
+
+        for location_id in &location_ids {
+            if let Some(local_market) = self.locations.get_mut(location_id) {
+                local_market.buy_offers.clear();
+                local_market.sell_offers.clear();
+            }
+        }
+
+        // Sorted for the same reason as `run_production_step`: replaying a
+        // saved `Market` must visit agents in the same order every time, and
+        // `HashMap` iteration order doesn't survive a save/load round-trip.
+        let mut agent_ids: Vec<AgentId> = self.agents.keys().cloned().collect();
+        agent_ids.sort();
+
+        for agent_id in agent_ids {
+            let Some(agent) = self.agents.get(&agent_id) else {
+                continue;
+            };
+
+            let offers = match scripted_decisions.remove(&agent_id) {
+                // Scripted offers aren't bounded by the agent's own inventory
+                // the way `get_trade_offers`'s are, so clamp (or drop) each
+                // one before it can reach the order book.
+                Some(decision) => decision
+                    .trade_offers
+                    .into_iter()
+                    .filter_map(|offer| agent.clamp_trade_offer(offer))
+                    .collect(),
+                None => agent.get_trade_offers(&mut self.rng),
+            };
+
+            let Some(local_market) = self.locations.get_mut(&agent.location_id) else {
+                continue;
+            };
+
+            for offer in offers {
+                if offer.is_buy {
+                    local_market
+                        .buy_offers
+                        .entry(offer.commodity_name.clone())
+                        .or_default()
+                        .push(offer);
+                } else {
+                    local_market
+                        .sell_offers
+                        .entry(offer.commodity_name.clone())
+                        .or_default()
+                        .push(Reverse(offer));
+                }
+            }
+        }
+
+        for location_id in &location_ids {
+            let Some(local_market) = self.locations.get(location_id) else {
+                continue;
+            };
+
+            let commodity_names: HashSet<CommodityName> = local_market
+                .buy_offers
+                .keys()
+                .chain(local_market.sell_offers.keys())
+                .cloned()
+                .collect();
+            // Sorted so clearing order (which affects who has budget left for
+            // the next commodity) is reproducible across a save/load replay.
+            let mut commodity_names: Vec<CommodityName> = commodity_names.into_iter().collect();
+            commodity_names.sort();
+
+            let mut traded_agents: HashMap<CommodityName, HashSet<AgentId>> = HashMap::new();
+            for commodity_name in &commodity_names {
+                let traded = self.clear_commodity(location_id, commodity_name);
+                traded_agents.insert(commodity_name.clone(), traded);
+            }
+
+            let historic_prices: HashMap<CommodityName, Money> = commodity_names
+                .iter()
+                .map(|commodity_name| {
+                    (
+                        commodity_name.clone(),
+                        self.get_historic_price(location_id, commodity_name),
+                    )
+                })
+                .collect();
+
+            // Anything still sitting in the heaps after clearing went unfilled
+            // this step; push each straggler's price belief away from its
+            // ask/bid. A requeued partial fill's remainder stays in the heap
+            // too, but its agent already got `update_filled` from the trade
+            // that produced the remainder, so it's excluded here to avoid
+            // pushing the same belief in both directions in one step.
+            let Some(local_market) = self.locations.get(location_id) else {
+                continue;
+            };
+
+            for (commodity_name, heap) in local_market.buy_offers.iter() {
+                let historic_price = historic_prices[commodity_name];
+                let traded = traded_agents.get(commodity_name);
+                for bid in heap.iter() {
+                    if traded.is_some_and(|traded| traded.contains(&bid.agent_id)) {
+                        continue;
+                    }
+                    if let Some(agent) = self.agents.get_mut(&bid.agent_id) {
+                        agent
+                            .price_belief_mut(commodity_name)
+                            .update_unfilled_buy(historic_price);
+                    }
+                }
+            }
+
+            for (commodity_name, heap) in local_market.sell_offers.iter() {
+                let historic_price = historic_prices[commodity_name];
+                let traded = traded_agents.get(commodity_name);
+                for Reverse(ask) in heap.iter() {
+                    if traded.is_some_and(|traded| traded.contains(&ask.agent_id)) {
+                        continue;
+                    }
+                    if let Some(agent) = self.agents.get_mut(&ask.agent_id) {
+                        agent
+                            .price_belief_mut(commodity_name)
+                            .update_unfilled_sell(historic_price);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Repeatedly matches the top bid against the top ask for `commodity_name`
+    /// at `location_id`, executing a trade at the mean clearing price as long
+    /// as the bid meets the ask, requeuing any unfilled remainder. Returns the
+    /// agents that traded (fully or partially) this call, so the caller can
+    /// tell a requeued partial fill apart from an offer that never matched at
+    /// all — the former already got its price belief updated here, and
+    /// shouldn't also get the unfilled-offer update for its leftover amount.
+    fn clear_commodity(
+        &mut self,
+        location_id: &LocationId,
+        commodity_name: &CommodityName,
+    ) -> HashSet<AgentId> {
+        let mut traded_agents = HashSet::new();
+
+        loop {
+            let (bid, ask) = {
+                let Some(local_market) = self.locations.get_mut(location_id) else {
+                    break;
+                };
+
+                let bids = local_market.buy_offers.get_mut(commodity_name);
+                let asks = local_market.sell_offers.get_mut(commodity_name);
+
+                let (Some(bids), Some(asks)) = (bids, asks) else {
+                    break;
+                };
+
+                let top_bid_price = match bids.peek() {
+                    Some(bid) => bid.price,
+                    None => break,
+                };
+                let top_ask_price = match asks.peek() {
+                    Some(Reverse(ask)) => ask.price,
+                    None => break,
+                };
+
+                if top_bid_price < top_ask_price {
+                    break;
+                }
+
+                (bids.pop().unwrap(), asks.pop().unwrap().0)
+            };
+
+            if bid.agent_id == ask.agent_id {
+                // An agent can't trade with itself; drop both offers and move on.
+                continue;
+            }
+
+            let clearing_price = bid.price.midpoint(ask.price);
+            let mut buyer = self.agents.remove(&bid.agent_id).unwrap();
+            let mut seller = self.agents.remove(&ask.agent_id).unwrap();
+
+            let affordable_amount = if clearing_price.is_positive() {
+                (buyer.balance.raw() / clearing_price.raw()).max(0) as u32
+            } else {
+                bid.max_amount
+            };
+            let amount = bid.max_amount.min(ask.max_amount).min(affordable_amount);
+
+            if amount > 0 {
+                let payment = clearing_price
+                    .checked_mul_amount(amount)
+                    .expect("affordable_amount already bounds this by the buyer's balance");
+
+                buyer
+                    .inventories
+                    .get_mut(commodity_name)
+                    .unwrap()
+                    .add(amount)
+                    .expect("bid.max_amount was bounded by the buyer's free capacity");
+                buyer.balance = buyer
+                    .balance
+                    .checked_sub_nonnegative(payment)
+                    .expect("affordable_amount already bounds payment by the buyer's balance");
+
+                seller
+                    .inventories
+                    .get_mut(commodity_name)
+                    .unwrap()
+                    .remove(amount)
+                    .expect("ask.max_amount was bounded by the seller's unreserved stock");
+                seller.balance = seller
+                    .balance
+                    .checked_add(payment)
+                    .expect("a seller's balance growing by a bounded payment should not overflow");
+
+                if let Some(local_market) = self.locations.get_mut(location_id) {
+                    local_market
+                        .trades
+                        .entry(commodity_name.clone())
+                        .or_default()
+                        .push(Trade {
+                            buyer_id: bid.agent_id,
+                            seller_id: ask.agent_id,
+                            commodity_name: commodity_name.clone(),
+                            price: clearing_price,
+                            amount,
+                        });
+                }
+
+                buyer
+                    .price_belief_mut(commodity_name)
+                    .update_filled(clearing_price);
+                seller
+                    .price_belief_mut(commodity_name)
+                    .update_filled(clearing_price);
+
+                traded_agents.insert(bid.agent_id);
+                traded_agents.insert(ask.agent_id);
+            }
+
+            self.agents.insert(bid.agent_id, buyer);
+            self.agents.insert(ask.agent_id, seller);
+
+            let Some(local_market) = self.locations.get_mut(location_id) else {
+                break;
+            };
+
+            if amount == 0 {
+                // Neither side could be satisfied any further at this price; put
+                // both offers back and stop trying to clear this commodity.
+                local_market.buy_offers.get_mut(commodity_name).unwrap().push(bid);
+                local_market
+                    .sell_offers
+                    .get_mut(commodity_name)
+                    .unwrap()
+                    .push(Reverse(ask));
+                break;
+            }
+
+            if bid.max_amount > amount {
+                local_market
+                    .buy_offers
+                    .get_mut(commodity_name)
+                    .unwrap()
+                    .push(TradeOffer {
+                        max_amount: bid.max_amount - amount,
+                        ideal_amount: bid.ideal_amount.saturating_sub(amount),
+                        ..bid
+                    });
+            }
+
+            if ask.max_amount > amount {
+                local_market
+                    .sell_offers
+                    .get_mut(commodity_name)
+                    .unwrap()
+                    .push(Reverse(TradeOffer {
+                        max_amount: ask.max_amount - amount,
+                        ideal_amount: ask.ideal_amount.saturating_sub(amount),
+                        ..ask
+                    }));
+            }
+        }
+
+        traded_agents
+    }
+}
+
+mod tests {
+    #[test]
+    fn clear_commodity_matches_top_bid_against_top_ask_at_the_midpoint_price() {
+        let mut market = crate::Market::default();
+        market.add_production_strategy("farmer").add_input("water", 1);
+        let location_id = market.add_location((0.0, 0.0));
+
+        let buyer_id = {
+            let mut builder = market.add_agent(location_id);
+            builder.add_production_strategy("farmer");
+            builder.agent.id
+        };
+        let seller_id = {
+            let mut builder = market.add_agent(location_id);
+            builder.add_production_strategy("farmer");
+            builder.agent.id
+        };
+
+        {
+            let local_market = market.locations.get_mut(&location_id).unwrap();
+            local_market.buy_offers.insert(
+                "water".to_string(),
+                std::collections::BinaryHeap::from(vec![crate::TradeOffer {
+                    agent_id: buyer_id,
+                    commodity_name: "water".to_string(),
+                    is_buy: true,
+                    ideal_amount: 5,
+                    max_amount: 5,
+                    price: crate::Money::from_raw(30),
+                }]),
+            );
+            local_market.sell_offers.insert(
+                "water".to_string(),
+                std::collections::BinaryHeap::from(vec![std::cmp::Reverse(crate::TradeOffer {
+                    agent_id: seller_id,
+                    commodity_name: "water".to_string(),
+                    is_buy: false,
+                    ideal_amount: 5,
+                    max_amount: 5,
+                    price: crate::Money::from_raw(20),
+                })]),
+            );
+        }
+
+        market.clear_commodity(&location_id, &"water".to_string());
+
+        let buyer = market.agents.get(&buyer_id).unwrap();
+        let seller = market.agents.get(&seller_id).unwrap();
+
+        // Clears at the bid/ask midpoint (25); the buyer's balance of 100
+        // only affords 4 units at that price, so the trade is capped there
+        // even though both offers asked for 5.
+        assert_eq!(buyer.inventories.get("water").unwrap().amount, 14);
+        assert_eq!(buyer.balance, crate::Money::ZERO);
+        assert_eq!(seller.inventories.get("water").unwrap().amount, 6);
+        assert_eq!(seller.balance, crate::Money::from_raw(200));
+
+        let local_market = market.locations.get(&location_id).unwrap();
+        let trades = local_market.trades.get("water").unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].amount, 4);
+        assert_eq!(trades[0].price, crate::Money::from_raw(25));
+
+        // The unfilled remainder of each offer gets requeued.
+        assert_eq!(
+            local_market.buy_offers.get("water").unwrap().peek().unwrap().max_amount,
+            1
+        );
+        assert_eq!(
+            local_market
+                .sell_offers
+                .get("water")
+                .unwrap()
+                .peek()
+                .unwrap()
+                .0
+                .max_amount,
+            1
+        );
+    }
+
+    #[test]
+    fn transport_charges_balance_and_relocates_agent() {
+        let mut market = crate::Market::default();
+        market.add_production_strategy("farmer").add_input("water", 1);
+
+        let origin = market.add_location((0.0, 0.0));
+        let destination = market.add_location((3.0, 4.0));
+
+        let agent_id = {
+            let mut builder = market.add_agent(origin);
+            builder.add_production_strategy("farmer");
+            builder.agent.id
+        };
+
+        let balance_before = market.agents.get(&agent_id).unwrap().balance;
+        let held = market
+            .agents
+            .get(&agent_id)
+            .unwrap()
+            .inventory_amount(&"water".to_string());
+
+        market
+            .transport(agent_id, destination, &"water".to_string())
+            .expect("agent holds water and can afford the fee");
+
+        let agent = market.agents.get(&agent_id).unwrap();
+        assert_eq!(agent.location_id, destination);
+        assert!(agent.balance < balance_before);
+        assert!(held > 0);
+    }
+
+    #[test]
+    fn transport_rejects_a_commodity_the_agent_does_not_hold() {
+        let mut market = crate::Market::default();
+        market.add_production_strategy("farmer").add_input("water", 1);
+
+        let origin = market.add_location((0.0, 0.0));
+        let destination = market.add_location((1.0, 0.0));
+
+        let agent_id = {
+            let mut builder = market.add_agent(origin);
+            builder.add_production_strategy("farmer");
+            builder.agent.id
+        };
+
+        let result = market.transport(agent_id, destination, &"gold".to_string());
+
+        assert!(matches!(result, Err(super::TransportError::InsufficientStock)));
+    }
+}
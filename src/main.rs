@@ -1,19 +1,40 @@
-use core::panic;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{HashMap, HashSet};
 
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 const DEFAULT_INVENTORY_CAPACITY: u32 = 100;
 const DEFAULT_INVENTORY_AMOUNT: u32 = 10;
-const DEFAULT_BALANCE: i32 = 100;
-
-type AgentId = Uuid;
-type CommodityName = String;
-type ProductionStrategyName = String;
+const DEFAULT_BALANCE: Money = Money::from_raw(100);
+
+// Doran-Parberry price-belief tuning: how far a filled offer's mean moves
+// toward the realized trade price, how much a filled belief narrows, and how
+// much an unfilled belief widens per step.
+const PRICE_BELIEF_FILL_SHIFT: f64 = 0.075;
+const PRICE_BELIEF_FILL_SHRINK: f64 = 0.95;
+const PRICE_BELIEF_MISS_SHIFT: f64 = 0.2;
+const PRICE_BELIEF_MISS_WIDEN: f64 = 1.1;
+
+mod location;
+mod money;
+mod persistence;
+mod planner;
+mod scripting;
+
+pub(crate) use location::LocalMarket;
+pub(crate) use money::Money;
+
+pub(crate) type AgentId = Uuid;
+pub(crate) type CommodityName = String;
+pub(crate) type ProductionStrategyName = String;
+pub(crate) type LocationId = Uuid;
 
 fn main() {
     println!("Hello, world!");
-    let mut market = Market::default();
+    let mut market = Market::seeded(42);
+    let homestead = market.add_location((0.0, 0.0));
 
     market
         .add_production_strategy("farmer")
@@ -25,12 +46,16 @@ fn main() {
         .add_production_strategy("water-source")
         .add_output("water", 1);
 
-    market.add_agent().add_production_strategy("farmer");
+    market
+        .add_agent(homestead)
+        .add_production_strategy("farmer");
 
-    market.add_agent().add_production_strategy("water-source");
+    market
+        .add_agent(homestead)
+        .add_production_strategy("water-source");
 
     market
-        .add_agent()
+        .add_agent(homestead)
         .add_production_strategy("farmer")
         .add_production_strategy("water-source");
 
@@ -44,18 +69,81 @@ fn main() {
     market.run_production_step();
     market.run_production_step();
     println!("{:#?}", market.agents);
+    println!("===================");
+
+    for _ in 0..10 {
+        market.run_step();
+    }
+
+    println!("{:#?}", market.agents);
+    println!("===================");
+    for location in market.locations.values() {
+        for (commodity_name, trades) in &location.trades {
+            for trade in trades {
+                println!(
+                    "{} units of {} traded between {} and {} at {} (historic price: {})",
+                    trade.amount,
+                    trade.commodity_name,
+                    trade.buyer_id,
+                    trade.seller_id,
+                    trade.price,
+                    market.get_historic_price(&homestead, commodity_name)
+                );
+            }
+        }
+    }
+
+    println!("===================");
+    let snapshot_path = "market_snapshot.yaml";
+    market
+        .save(snapshot_path)
+        .expect("failed to save market snapshot");
+    let mut replayed = Market::load(snapshot_path).expect("failed to load market snapshot");
+
+    market.replay(5);
+    replayed.replay(5);
+
+    // `HashMap::eq` compares contents regardless of iteration order, so this
+    // is unaffected by the per-instance randomized hasher that makes a
+    // deserialized map iterate (and `Debug`-print) in a different order than
+    // the original.
+    println!(
+        "replay from snapshot is deterministic: {}",
+        market.agents == replayed.agents
+    );
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Market {
-    pub buy_offers: HashMap<CommodityName, BinaryHeap<Trade>>,
-    pub sell_offers: HashMap<CommodityName, BinaryHeap<Trade>>,
     pub agents: HashMap<Uuid, Agent>,
     pub production_strategies: HashMap<ProductionStrategyName, ProductionStrategy>,
-    pub trades: HashMap<CommodityName, Vec<Trade>>,
+    pub locations: HashMap<LocationId, LocalMarket>,
+    /// Seeded so a saved `Market` replays identically: every step that would
+    /// otherwise reach for `rand::thread_rng()` draws from this instead.
+    rng: ChaCha8Rng,
+}
+
+impl Default for Market {
+    fn default() -> Self {
+        Self {
+            agents: HashMap::new(),
+            production_strategies: HashMap::new(),
+            locations: HashMap::new(),
+            rng: ChaCha8Rng::from_entropy(),
+        }
+    }
 }
 
 impl Market {
+    /// Like `Market::default`, but with the RNG seeded explicitly so the run
+    /// is reproducible; see `Market::replay`.
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            ..Self::default()
+        }
+    }
+
     pub fn add_production_strategy(&mut self, name: &str) -> &mut ProductionStrategy {
         let production_strategy = ProductionStrategy::new();
 
@@ -65,8 +153,8 @@ impl Market {
         self.production_strategies.get_mut(name).unwrap()
     }
 
-    pub fn add_agent(&mut self) -> MarketAgentBuilder {
-        let agent = Agent::new();
+    pub fn add_agent(&mut self, location_id: LocationId) -> MarketAgentBuilder<'_> {
+        let agent = Agent::new(location_id);
         let agent_id = agent.id;
         self.agents.insert(agent.id, agent);
 
@@ -76,7 +164,7 @@ impl Market {
         }
     }
 
-    pub fn get_agents_mut(&mut self) -> impl Iterator<Item = MarketAgentBuilder> {
+    pub fn get_agents_mut(&mut self) -> impl Iterator<Item = MarketAgentBuilder<'_>> {
         self.agents
             .iter_mut()
             .map(|(_, agent)| -> MarketAgentBuilder {
@@ -87,43 +175,123 @@ impl Market {
             })
     }
 
-    // TODO: Memoize
-    fn get_historic_price(&self, commodity_name: &CommodityName) -> i32 {
-        if let Some(trades) = self.trades.get(commodity_name) {
-            trades.iter().map(|trade| trade.price).sum::<i32>() / trades.len() as i32
-        } else {
-            0
-        }
+    /// Runs one production step standalone, evaluating each scripted agent's
+    /// script just for this call. Prefer `run_step` when pairing this with
+    /// `run_market_step`, so a scripted agent's script runs once for both
+    /// instead of once per call.
+    pub fn run_production_step(&mut self) {
+        let scripted_decisions = self.run_scripts();
+        self.run_production_step_with_decisions(&scripted_decisions);
     }
 
-    pub fn run_production_step(&mut self) {
-        self.agents
-            .iter_mut()
-            .for_each(|(_, agent)| agent.run_production_step(&self.production_strategies))
+    /// Runs one full step: production, then market clearing, evaluating each
+    /// scripted agent's script exactly once and sharing the decision between
+    /// both phases.
+    pub fn run_step(&mut self) {
+        let scripted_decisions = self.run_scripts();
+        self.run_production_step_with_decisions(&scripted_decisions);
+        self.run_market_step_with_decisions(scripted_decisions);
+    }
+
+    pub(crate) fn run_production_step_with_decisions(
+        &mut self,
+        scripted_decisions: &HashMap<AgentId, crate::scripting::ScriptedDecision>,
+    ) {
+        // Sorted so replaying a saved `Market` visits agents in the same
+        // order every time: `HashMap` iteration order is randomized per
+        // instance and wouldn't otherwise survive a save/load round-trip.
+        let mut agent_ids: Vec<AgentId> = self.agents.keys().cloned().collect();
+        agent_ids.sort();
+
+        for agent_id in agent_ids {
+            let Some(agent) = self.agents.get_mut(&agent_id) else {
+                continue;
+            };
+
+            let active_producers = scripted_decisions
+                .get(&agent_id)
+                .map(|decision| &decision.active_producers);
+
+            agent.run_production_step_filtered(&self.production_strategies, active_producers);
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Trade {
     buyer_id: AgentId,
     seller_id: AgentId,
     commodity_name: CommodityName,
-    price: i32,
+    price: Money,
+    amount: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PriceBelief {
-    upper: i32,
-    lower: i32,
+    upper: Money,
+    lower: Money,
 }
 
 impl PriceBelief {
     fn new() -> Self {
         Self {
-            upper: 100,
-            lower: 0,
+            upper: Money::from_raw(100),
+            lower: Money::ZERO,
         }
     }
+
+    fn mean(&self) -> i64 {
+        (self.upper.raw() + self.lower.raw()) / 2
+    }
+
+    fn width(&self) -> i64 {
+        self.upper.raw() - self.lower.raw()
+    }
+
+    /// Samples a candidate offer price uniformly from `[lower, upper]`.
+    fn sample(&self, rng: &mut impl Rng) -> Money {
+        if self.upper <= self.lower {
+            self.lower
+        } else {
+            Money::from_raw(rng.gen_range(self.lower.raw()..=self.upper.raw()))
+        }
+    }
+
+    /// The offer this belief priced was matched and traded at `trade_price`:
+    /// nudge the mean toward the realized price and narrow the interval, since
+    /// the agent now has better information about where the market clears.
+    fn update_filled(&mut self, trade_price: Money) {
+        let shift = ((trade_price.raw() - self.mean()) as f64 * PRICE_BELIEF_FILL_SHIFT) as i64;
+        let new_mean = self.mean() + shift;
+        let new_width = ((self.width() as f64) * PRICE_BELIEF_FILL_SHRINK).max(1.0) as i64;
+
+        self.lower = Money::from_raw((new_mean - new_width / 2).max(0));
+        self.upper = Money::from_raw(self.lower.raw() + new_width);
+    }
+
+    /// A buy offer went unfilled: the agent bid too low relative to the
+    /// market, so translate the interval up toward the historic price and
+    /// widen it to hedge against the miss.
+    fn update_unfilled_buy(&mut self, historic_price: Money) {
+        let gap = (historic_price.raw() - self.upper.raw()).max(0);
+        let shift = (gap as f64 * PRICE_BELIEF_MISS_SHIFT) as i64;
+        let new_width = ((self.width() as f64) * PRICE_BELIEF_MISS_WIDEN).max(1.0) as i64;
+
+        self.lower = Money::from_raw((self.lower.raw() + shift).max(0));
+        self.upper = Money::from_raw(self.lower.raw() + new_width);
+    }
+
+    /// A sell offer went unfilled: the agent asked too high relative to the
+    /// market, so translate the interval down toward the historic price and
+    /// widen it.
+    fn update_unfilled_sell(&mut self, historic_price: Money) {
+        let gap = (self.lower.raw() - historic_price.raw()).max(0);
+        let shift = (gap as f64 * PRICE_BELIEF_MISS_SHIFT) as i64;
+        let new_width = ((self.width() as f64) * PRICE_BELIEF_MISS_WIDEN).max(1.0) as i64;
+
+        self.lower = Money::from_raw((self.lower.raw() - shift).max(0));
+        self.upper = Money::from_raw(self.lower.raw() + new_width);
+    }
 }
 
 pub struct MarketAgentBuilder<'a> {
@@ -158,16 +326,21 @@ impl MarketAgentBuilder<'_> {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Agent {
     pub id: Uuid,
+    pub location_id: LocationId,
     pub inventories: HashMap<CommodityName, Inventory>,
     pub producers: Vec<Producer>,
-    pub balance: i32,
+    pub balance: Money,
     pub price_beliefs: HashMap<CommodityName, PriceBelief>,
+    /// Optional Lua source overriding this agent's decision-making; see
+    /// `scripting::ScriptedDecision`. `None` falls back to the built-in
+    /// inventory-shortfall heuristic in `get_trade_offers`.
+    pub script: Option<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Producer {
     production_strategy_name: ProductionStrategyName,
     progress: u32,
@@ -183,9 +356,10 @@ impl Producer {
 }
 
 impl Agent {
-    fn new() -> Self {
+    fn new(location_id: LocationId) -> Self {
         Self {
             id: Uuid::new_v4(),
+            location_id,
             balance: DEFAULT_BALANCE,
             ..Self::default()
         }
@@ -207,18 +381,135 @@ impl Agent {
         }
     }
 
-    pub fn get_trade_offers(&self) {
-        todo!()
+    /// Returns the agent's `PriceBelief` for `commodity_name`, creating a
+    /// fresh one if the agent hasn't traded it before.
+    pub fn price_belief_mut(&mut self, commodity_name: &CommodityName) -> &mut PriceBelief {
+        self.price_beliefs
+            .entry(commodity_name.clone())
+            .or_insert_with(PriceBelief::new)
+    }
+
+    /// Emits one bid or ask per commodity the agent holds an inventory for,
+    /// based on how far `amount` sits from `ideal_amount`. Agents short a
+    /// commodity bid for the shortfall; agents with a surplus ask to sell it.
+    /// The offer price is sampled uniformly from the agent's `PriceBelief`,
+    /// then biased by how far `amount` is from `ideal_amount`: starved agents
+    /// bid closer to the belief's upper bound, and agents drowning in surplus
+    /// ask closer to its lower bound.
+    pub fn get_trade_offers(&self, rng: &mut impl Rng) -> Vec<TradeOffer> {
+        let default_belief = PriceBelief::new();
+
+        // Sorted so replaying a saved `Market` draws from `rng` in the same
+        // per-commodity order every time: `HashMap` iteration order is
+        // randomized per instance and wouldn't otherwise survive a save/load
+        // round-trip.
+        let mut commodity_names: Vec<&CommodityName> = self.inventories.keys().collect();
+        commodity_names.sort();
+
+        commodity_names
+            .into_iter()
+            .filter_map(|commodity_name| {
+                let inventory = self.inventories.get(commodity_name).unwrap();
+                let shortfall = inventory.ideal_amount.saturating_sub(inventory.amount);
+                let surplus = inventory.amount.saturating_sub(inventory.ideal_amount);
+
+                if shortfall == 0 && surplus == 0 {
+                    return None;
+                }
+
+                let belief = self
+                    .price_beliefs
+                    .get(commodity_name)
+                    .unwrap_or(&default_belief);
+                let sampled_price = belief.sample(rng);
+                let scarcity_ratio =
+                    inventory.amount as f64 / inventory.ideal_amount.max(1) as f64;
+
+                if shortfall > 0 {
+                    let urgency = (1.0 - scarcity_ratio).clamp(0.0, 1.0);
+                    let price = Money::from_raw(
+                        sampled_price.raw()
+                            + ((belief.upper.raw() - sampled_price.raw()) as f64 * urgency) as i64,
+                    );
+
+                    Some(TradeOffer {
+                        agent_id: self.id,
+                        commodity_name: commodity_name.clone(),
+                        is_buy: true,
+                        ideal_amount: shortfall,
+                        max_amount: inventory.free().min(shortfall),
+                        price,
+                    })
+                } else {
+                    let eagerness = (scarcity_ratio - 1.0).clamp(0.0, 1.0);
+                    let price = Money::from_raw(
+                        sampled_price.raw()
+                            - ((sampled_price.raw() - belief.lower.raw()) as f64 * eagerness) as i64,
+                    );
+
+                    Some(TradeOffer {
+                        agent_id: self.id,
+                        commodity_name: commodity_name.clone(),
+                        is_buy: false,
+                        ideal_amount: surplus,
+                        max_amount: inventory.unreserved().min(surplus),
+                        price,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Clamps an offer to what this agent can actually fulfill. `get_trade_offers`
+    /// already bounds `max_amount` by the agent's free capacity/unreserved
+    /// stock, but a scripted offer (see `run_script`) makes no such promise,
+    /// so `run_market_step` runs every scripted offer through this before it
+    /// reaches the order book — otherwise clearing it would panic. Returns
+    /// `None` if the agent holds no inventory for the commodity at all.
+    pub(crate) fn clamp_trade_offer(&self, mut offer: TradeOffer) -> Option<TradeOffer> {
+        let inventory = self.inventories.get(&offer.commodity_name)?;
+
+        let available = if offer.is_buy {
+            inventory.free()
+        } else {
+            inventory.unreserved()
+        };
+
+        offer.max_amount = offer.max_amount.min(available);
+        offer.ideal_amount = offer.ideal_amount.min(offer.max_amount);
+
+        if offer.max_amount == 0 {
+            return None;
+        }
+
+        Some(offer)
     }
 
     pub fn run_production_step(
         &mut self,
         production_strategies: &HashMap<ProductionStrategyName, ProductionStrategy>,
+    ) {
+        self.run_production_step_filtered(production_strategies, None);
+    }
+
+    /// Like `run_production_step`, but if `active_producers` is `Some`, only
+    /// producers named in it make progress this step; the rest sit idle. Lets
+    /// a `script`'s decision about which producers to run take effect.
+    pub fn run_production_step_filtered(
+        &mut self,
+        production_strategies: &HashMap<ProductionStrategyName, ProductionStrategy>,
+        active_producers: Option<&HashSet<ProductionStrategyName>>,
     ) {
         let producers = self.producers.iter_mut();
         let inventories = &mut self.inventories;
 
         producers.for_each(|producer| {
+            if let Some(active_producers) = active_producers {
+                if !active_producers.contains(&producer.production_strategy_name) {
+                    return;
+                }
+            }
+
             let production_strategy = production_strategies
                 .get(&producer.production_strategy_name)
                 .unwrap();
@@ -247,7 +538,9 @@ impl Agent {
                                 .get_mut(&production_requirement.commodity_name)
                                 .unwrap();
 
-                            inventory.reserve(production_requirement.amount);
+                            inventory
+                                .reserve(production_requirement.amount)
+                                .expect("inputs_are_satisfied already checked unreserved stock");
                         });
 
                     producer.progress += 1;
@@ -273,8 +566,12 @@ impl Agent {
                                 .get_mut(&production_requirement.commodity_name)
                                 .unwrap();
 
-                            inventory.remove(production_requirement.amount);
-                            inventory.unreserve(production_requirement.amount);
+                            inventory
+                                .remove(production_requirement.amount)
+                                .expect("input was reserved, so stock to remove is available");
+                            inventory
+                                .unreserve(production_requirement.amount)
+                                .expect("input was reserved, so it can be unreserved");
                         });
 
                     production_strategy
@@ -285,7 +582,9 @@ impl Agent {
                                 .get_mut(&production_requirement.commodity_name)
                                 .unwrap();
 
-                            inventory.add(production_requirement.amount);
+                            inventory
+                                .add(production_requirement.amount)
+                                .expect("has_room_for_outputs already checked free capacity");
                         });
 
                     producer.progress = 0;
@@ -297,7 +596,14 @@ impl Agent {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InventoryError {
+    InsufficientCapacity,
+    InsufficientStock,
+    InsufficientReserve,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Inventory {
     pub capacity: u32,
     pub amount: u32,
@@ -315,20 +621,30 @@ impl Inventory {
         }
     }
 
-    fn add(&mut self, amount: u32) {
+    fn add(&mut self, amount: u32) -> Result<(), InventoryError> {
         if amount > self.free() {
-            panic!("Tried to add more than there is room for")
+            return Err(InventoryError::InsufficientCapacity);
         }
 
-        self.amount += amount;
+        self.amount = self
+            .amount
+            .checked_add(amount)
+            .ok_or(InventoryError::InsufficientCapacity)?;
+
+        Ok(())
     }
 
-    fn remove(&mut self, amount: u32) {
-        if amount > self.free() {
-            panic!("Tried to remove more than is available")
+    fn remove(&mut self, amount: u32) -> Result<(), InventoryError> {
+        if amount > self.amount {
+            return Err(InventoryError::InsufficientStock);
         }
 
-        self.amount -= amount;
+        self.amount = self
+            .amount
+            .checked_sub(amount)
+            .ok_or(InventoryError::InsufficientStock)?;
+
+        Ok(())
     }
 
     fn free(&self) -> u32 {
@@ -339,20 +655,30 @@ impl Inventory {
         self.amount - self.reserved
     }
 
-    fn reserve(&mut self, amount: u32) {
+    fn reserve(&mut self, amount: u32) -> Result<(), InventoryError> {
         if amount > self.unreserved() {
-            panic!("Tried to reserve more than is available")
+            return Err(InventoryError::InsufficientStock);
         }
 
-        self.reserved += amount;
+        self.reserved = self
+            .reserved
+            .checked_add(amount)
+            .ok_or(InventoryError::InsufficientStock)?;
+
+        Ok(())
     }
 
-    fn unreserve(&mut self, amount: u32) {
-        if amount > self.unreserved() {
-            panic!("Tried to unreserve more than is reserved")
+    fn unreserve(&mut self, amount: u32) -> Result<(), InventoryError> {
+        if amount > self.reserved {
+            return Err(InventoryError::InsufficientReserve);
         }
 
-        self.reserved -= amount;
+        self.reserved = self
+            .reserved
+            .checked_sub(amount)
+            .ok_or(InventoryError::InsufficientReserve)?;
+
+        Ok(())
     }
 }
 
@@ -378,7 +704,7 @@ pub fn get_inventory_capacity(
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ProductionRequirement {
     pub commodity_name: CommodityName,
     pub amount: u32,
@@ -393,7 +719,7 @@ impl ProductionRequirement {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct ProductionStrategy {
     pub inputs: Vec<ProductionRequirement>,
     pub outputs: Vec<ProductionRequirement>,
@@ -430,12 +756,37 @@ impl ProductionStrategy {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TradeOffer {
-    pub commodity_name: String,
-    pub ideal_amount: i32,
-    pub max_amount: i32,
-    pub price: i32,
+    pub agent_id: AgentId,
+    pub commodity_name: CommodityName,
+    pub is_buy: bool,
+    pub ideal_amount: u32,
+    pub max_amount: u32,
+    pub price: Money,
+}
+
+// `TradeOffer`s are ordered by price alone so they can sit in the market's
+// per-commodity bid/ask heaps: a max-heap of `TradeOffer` for bids, and a
+// min-heap (via `Reverse`) for asks.
+impl PartialEq for TradeOffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.price == other.price
+    }
+}
+
+impl Eq for TradeOffer {}
+
+impl PartialOrd for TradeOffer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TradeOffer {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.price.cmp(&other.price)
+    }
 }
 
 mod tests {
@@ -449,7 +800,10 @@ mod tests {
             .add_output("food", 1)
             .duration(1);
 
-        market.add_agent().add_production_strategy("farmer");
+        let location_id = market.add_location((0.0, 0.0));
+        market
+            .add_agent(location_id)
+            .add_production_strategy("farmer");
 
         {
             let agent = market.agents.iter().last().unwrap().1;
@@ -476,4 +830,111 @@ mod tests {
             assert_eq!(agent.inventories.get("food").unwrap().amount, 11);
         }
     }
+
+    #[test]
+    fn production_step_consumes_more_than_half_of_default_inventory() {
+        // Regression test: `remove` used to guard on `free()` instead of
+        // `amount`, and `unreserve` used to guard on `unreserved()` instead of
+        // `reserved`, so reserving/consuming more than half of the default
+        // 10-unit inventory (free capacity drops below the reserved/removed
+        // amount) spuriously errored or underflowed.
+        let mut market = crate::Market::default();
+
+        market
+            .add_production_strategy("farmer")
+            .add_input("water", 4)
+            .add_output("food", 1)
+            .duration(1);
+
+        let location_id = market.add_location((0.0, 0.0));
+        market
+            .add_agent(location_id)
+            .add_production_strategy("farmer");
+
+        market.run_production_step();
+        market.run_production_step();
+
+        let agent = market.agents.iter().last().unwrap().1;
+        assert_eq!(agent.producers.last().unwrap().progress, 0);
+        assert_eq!(agent.inventories.get("water").unwrap().amount, 6);
+        assert_eq!(agent.inventories.get("food").unwrap().amount, 11);
+    }
+
+    #[test]
+    fn clamp_trade_offer_bounds_a_scripted_buy_to_free_capacity() {
+        let mut market = crate::Market::default();
+
+        market.add_production_strategy("farmer").add_input("water", 1);
+        let location_id = market.add_location((0.0, 0.0));
+        market
+            .add_agent(location_id)
+            .add_production_strategy("farmer");
+
+        let agent = market.agents.values().next().unwrap();
+        let free = agent.inventories.get("water").unwrap().free();
+
+        let offer = crate::TradeOffer {
+            agent_id: agent.id,
+            commodity_name: "water".to_string(),
+            is_buy: true,
+            ideal_amount: free + 50,
+            max_amount: free + 50,
+            price: crate::Money::from_raw(1),
+        };
+
+        let clamped = agent
+            .clamp_trade_offer(offer)
+            .expect("agent holds an inventory for water");
+        assert_eq!(clamped.max_amount, free);
+        assert_eq!(clamped.ideal_amount, free);
+    }
+
+    #[test]
+    fn clamp_trade_offer_drops_offers_for_unheld_commodities() {
+        let agent = crate::Agent::default();
+
+        let offer = crate::TradeOffer {
+            agent_id: agent.id,
+            commodity_name: "gold".to_string(),
+            is_buy: true,
+            ideal_amount: 5,
+            max_amount: 5,
+            price: crate::Money::from_raw(1),
+        };
+
+        assert!(agent.clamp_trade_offer(offer).is_none());
+    }
+
+    #[test]
+    fn price_belief_update_filled_shifts_mean_toward_trade_price_and_narrows() {
+        let mut belief = crate::PriceBelief::new();
+
+        belief.update_filled(crate::Money::from_raw(80));
+
+        assert_eq!(belief.lower, crate::Money::from_raw(5));
+        assert_eq!(belief.upper, crate::Money::from_raw(100));
+    }
+
+    #[test]
+    fn price_belief_update_unfilled_buy_shifts_up_and_widens() {
+        let mut belief = crate::PriceBelief::new();
+
+        belief.update_unfilled_buy(crate::Money::from_raw(150));
+
+        assert_eq!(belief.lower, crate::Money::from_raw(10));
+        assert_eq!(belief.upper, crate::Money::from_raw(120));
+    }
+
+    #[test]
+    fn price_belief_update_unfilled_sell_shifts_down_and_widens() {
+        let mut belief = crate::PriceBelief {
+            upper: crate::Money::from_raw(100),
+            lower: crate::Money::from_raw(50),
+        };
+
+        belief.update_unfilled_sell(crate::Money::from_raw(20));
+
+        assert_eq!(belief.lower, crate::Money::from_raw(44));
+        assert_eq!(belief.upper, crate::Money::from_raw(99));
+    }
 }
